@@ -2,155 +2,774 @@
 
 use std::fmt;
 use std::cmp::PartialEq;
-use stdweb::web::{INode, Node, Element, TextNode, document};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::iter::FromIterator;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use stdweb::web::{IElement, INode, Node, Element, TextNode, document};
 use virtual_dom::{VTag, VText, Messages};
 
-/// Bind virtual element to a DOM reference.
-pub enum VNode<MSG> {
-    /// A bind between `VTag` and `Element`.
+/// Abstracts the handful of DOM operations `VNode::apply` needs, so a
+/// vtree can be materialized against a real browser DOM or rendered
+/// headlessly (server-side rendering, tests) by swapping the backend.
+pub trait RenderBackend: Sized {
+    /// A mounted element node.
+    type Element: Clone;
+    /// A mounted text node.
+    type Text: Clone;
+    /// The common node type `Element` and `Text` can both be viewed as,
+    /// used to append/replace/remove either kind through one API.
+    type Node: Clone + From<Self::Element> + From<Self::Text>;
+
+    /// Create a new, unattached element with the given tag name.
+    fn create_element(tag: &str) -> Self::Element;
+    /// Create a new, unattached text node with the given content.
+    fn create_text_node(text: &str) -> Self::Text;
+    /// Append `child` as the last child of `parent`.
+    fn append_child(parent: &Self::Element, child: &Self::Node);
+    /// Replace `old` with `new` under `parent`.
+    fn replace_child(parent: &Self::Element, new: &Self::Node, old: &Self::Node);
+    /// Insert `new` as a child of `parent`, immediately before `reference`,
+    /// or as the last child if `reference` is `None`. If `new` is already
+    /// mounted elsewhere under `parent` it is moved there instead of
+    /// duplicated; used to reposition keyed children after a reorder/insert.
+    fn insert_before(parent: &Self::Element, new: &Self::Node, reference: Option<&Self::Node>);
+    /// Remove `child` from `parent`.
+    fn remove_child(parent: &Self::Element, child: &Self::Node);
+    /// Set (or overwrite) an attribute on a mounted element, called by a
+    /// `VTag`'s `render` for each attribute it carries.
+    fn set_attribute(element: &Self::Element, name: &str, value: &str);
+}
+
+/// The default backend: renders directly into a real browser DOM via `stdweb`.
+pub struct StdwebBackend;
+
+impl RenderBackend for StdwebBackend {
+    type Element = Element;
+    type Text = TextNode;
+    type Node = Node;
+
+    fn create_element(tag: &str) -> Element {
+        document().create_element(tag)
+    }
+
+    fn create_text_node(text: &str) -> TextNode {
+        document().create_text_node(text)
+    }
+
+    fn append_child(parent: &Element, child: &Node) {
+        INode::append_child(parent, child);
+    }
+
+    fn replace_child(parent: &Element, new: &Node, old: &Node) {
+        let _ = INode::replace_child(parent, new, old);
+    }
+
+    fn insert_before(parent: &Element, new: &Node, reference: Option<&Node>) {
+        match reference {
+            Some(reference) => {
+                if let Err(_) = INode::insert_before(parent, new, reference) {
+                    warn!("Could not insert node before reference");
+                }
+            }
+            None => INode::append_child(parent, new),
+        }
+    }
+
+    fn remove_child(parent: &Element, child: &Node) {
+        if let Err(_) = INode::remove_child(parent, child) {
+            warn!("Node not found to remove: {:?}", child);
+        }
+    }
+
+    fn set_attribute(element: &Element, name: &str, value: &str) {
+        if let Err(_) = element.set_attribute(name, value) {
+            warn!("Could not set attribute {:?}", name);
+        }
+    }
+}
+
+/// An in-memory node: a reference-counted, interior-mutable handle, the
+/// same shape as a DOM reference but backed by plain Rust data instead of
+/// a real document. Lets a vtree be mounted and asserted against without
+/// a browser.
+#[derive(Clone)]
+pub struct MemoryElement(Rc<RefCell<MemoryElementData>>);
+
+struct MemoryElementData {
+    tag: String,
+    attrs: Vec<(String, String)>,
+    childs: Vec<MemoryNode>,
+}
+
+/// An in-memory text node.
+#[derive(Clone)]
+pub struct MemoryText(Rc<RefCell<String>>);
+
+/// Either kind of node the in-memory backend can hold as a child.
+#[derive(Clone)]
+pub enum MemoryNode {
+    /// A mounted in-memory element.
+    Element(MemoryElement),
+    /// A mounted in-memory text node.
+    Text(MemoryText),
+}
+
+impl From<MemoryElement> for MemoryNode {
+    fn from(element: MemoryElement) -> Self {
+        MemoryNode::Element(element)
+    }
+}
+
+impl From<MemoryText> for MemoryNode {
+    fn from(text: MemoryText) -> Self {
+        MemoryNode::Text(text)
+    }
+}
+
+impl PartialEq for MemoryNode {
+    fn eq(&self, other: &MemoryNode) -> bool {
+        match (self, other) {
+            (&MemoryNode::Element(ref a), &MemoryNode::Element(ref b)) => Rc::ptr_eq(&a.0, &b.0),
+            (&MemoryNode::Text(ref a), &MemoryNode::Text(ref b)) => Rc::ptr_eq(&a.0, &b.0),
+            _ => false,
+        }
+    }
+}
+
+/// A headless backend that mounts nodes as plain Rust values instead of a
+/// real DOM, for server-side rendering and tests that don't need a browser.
+pub struct MemoryBackend;
+
+impl RenderBackend for MemoryBackend {
+    type Element = MemoryElement;
+    type Text = MemoryText;
+    type Node = MemoryNode;
+
+    fn create_element(tag: &str) -> MemoryElement {
+        MemoryElement(Rc::new(RefCell::new(MemoryElementData {
+            tag: tag.to_string(),
+            attrs: Vec::new(),
+            childs: Vec::new(),
+        })))
+    }
+
+    fn create_text_node(text: &str) -> MemoryText {
+        MemoryText(Rc::new(RefCell::new(text.to_string())))
+    }
+
+    fn append_child(parent: &MemoryElement, child: &MemoryNode) {
+        parent.0.borrow_mut().childs.push(child.clone());
+    }
+
+    fn replace_child(parent: &MemoryElement, new: &MemoryNode, old: &MemoryNode) {
+        let mut data = parent.0.borrow_mut();
+        if let Some(pos) = data.childs.iter().position(|child| child == old) {
+            data.childs[pos] = new.clone();
+        }
+    }
+
+    fn insert_before(parent: &MemoryElement, new: &MemoryNode, reference: Option<&MemoryNode>) {
+        let mut data = parent.0.borrow_mut();
+        if let Some(pos) = data.childs.iter().position(|child| child == new) {
+            data.childs.remove(pos);
+        }
+        match reference {
+            Some(reference) => {
+                let pos = data.childs.iter().position(|child| child == reference).unwrap_or_else(|| data.childs.len());
+                data.childs.insert(pos, new.clone());
+            }
+            None => data.childs.push(new.clone()),
+        }
+    }
+
+    fn remove_child(parent: &MemoryElement, child: &MemoryNode) {
+        let mut data = parent.0.borrow_mut();
+        if let Some(pos) = data.childs.iter().position(|existing| existing == child) {
+            data.childs.remove(pos);
+        }
+    }
+
+    fn set_attribute(element: &MemoryElement, name: &str, value: &str) {
+        let mut data = element.0.borrow_mut();
+        match data.attrs.iter_mut().find(|pair| pair.0 == name) {
+            Some(pair) => pair.1 = value.to_string(),
+            None => data.attrs.push((name.to_string(), value.to_string())),
+        }
+    }
+}
+
+/// Tags the HTML spec declares void: they have no closing tag and cannot
+/// contain children, so `Display` for `MemoryElement` must not emit `</tag>`
+/// for them.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input",
+    "link", "meta", "param", "source", "track", "wbr",
+];
+
+impl fmt::Display for MemoryElement {
+    /// Serialize this element and its children to an HTML string, so a
+    /// `html!`-built vtree mounted against the in-memory backend can be
+    /// rendered on the server or asserted against in headless tests.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = self.0.borrow();
+        write!(f, "<{}", data.tag)?;
+        for &(ref name, ref value) in &data.attrs {
+            write!(f, " {}=\"{}\"", name, escape_html(value))?;
+        }
+        write!(f, ">")?;
+        if VOID_ELEMENTS.contains(&data.tag.as_str()) {
+            return Ok(());
+        }
+        for child in &data.childs {
+            match *child {
+                MemoryNode::Element(ref element) => write!(f, "{}", element)?,
+                MemoryNode::Text(ref text) => write!(f, "{}", escape_html(&text.0.borrow()))?,
+            }
+        }
+        write!(f, "</{}>", data.tag)
+    }
+}
+
+fn escape_html(raw: &str) -> String {
+    raw.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Identifies the placeholder slot `apply` reserves for a `VNode::Suspended`
+/// node, so code outside the diff (whatever is waiting on the async content)
+/// can tell which slot a given `Suspended` node was assigned once it's
+/// mounted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    fn next() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        NodeId(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Bind virtual element to a backend reference.
+pub enum VNode<MSG, B: RenderBackend = StdwebBackend> {
+    /// A bind between `VTag` and a mounted element.
     VTag {
-        /// A reference to the `Element`.
-        reference: Option<Element>,
+        /// A reference to the mounted element.
+        reference: Option<B::Element>,
         /// A virtual tag node which was applied.
-        vtag: VTag<MSG>,
+        vtag: VTag<MSG, B>,
     },
-    /// A bind between `VText` and `TextNode`.
+    /// A bind between `VText` and a mounted text node.
     VText {
-        /// A reference to the `TextNode`.
-        reference: Option<TextNode>,
+        /// A reference to the mounted text node.
+        reference: Option<B::Text>,
         /// A virtual text node which was applied.
         vtext: VText,
     },
+    /// A fragment of children with no element of its own, used to let a
+    /// render be rooted in more than one sibling node.
+    VList {
+        /// The child nodes of this fragment.
+        childs: Vec<VNode<MSG, B>>,
+    },
+    /// A slot for content that isn't ready yet (e.g. still loading).
+    /// Mounts as an empty placeholder so it reserves a stable position in
+    /// the sibling order; once the real content is available, a later
+    /// `apply` diffs it against the resolved `VTag`/`VText` in this slot
+    /// and swaps the placeholder out in place.
+    Suspended {
+        /// A reference to the mounted placeholder node.
+        reference: Option<B::Text>,
+        /// The id reserved for this slot, once `apply` has mounted it.
+        id: Cell<Option<NodeId>>,
+    },
+}
+
+
+/// One unit of work in a `Patcher`'s queue. Diffing a tree pushes these
+/// instead of recursing, so an arbitrarily deep/wide tree can be walked
+/// one instruction at a time instead of in a single synchronous burst.
+enum Instruction<MSG, B: RenderBackend> {
+    /// Diff `new` against `old`, both already known to exist.
+    Diff {
+        new: *mut VNode<MSG, B>,
+        old: VNode<MSG, B>,
+        parent: B::Element,
+    },
+    /// Mount `new`, which has no previous counterpart.
+    Create {
+        new: *mut VNode<MSG, B>,
+        parent: B::Element,
+    },
+    /// Detach `node` (and, for a `VList`, everything under it).
+    Remove {
+        node: VNode<MSG, B>,
+        parent: B::Element,
+    },
+    /// Reposition `childs` under `parent` to match their final order, once
+    /// every `Diff`/`Create` queued for them has run and each one is
+    /// mounted somewhere under `parent`. Only ever queued by the keyed
+    /// branch of `queue_childs`, where a match can reuse an old reference
+    /// that's no longer in the right sibling slot.
+    Reorder {
+        childs: Vec<*mut VNode<MSG, B>>,
+        parent: B::Element,
+    },
+}
+
+/// Drives a diff/apply pass as an explicit work-stack instead of Rust
+/// call-stack recursion. Call `step` repeatedly (e.g. from a
+/// `requestIdleCallback`/animation-frame scheduler) with a budget of how
+/// many instructions to process; it returns whether work remains.
+///
+/// Holds raw pointers into the tree being diffed rather than borrowing it
+/// for the `Patcher`'s lifetime, which is what lets the pass be paused
+/// across separate calls instead of running to completion in one go.
+///
+/// # Safety
+///
+/// Every `*mut VNode` queued here points at a slot that is never resized
+/// or relocated while a pointer into it is still pending: `create`/`diff`
+/// only ever *read* `childs` to collect `&mut` references to its existing
+/// elements (`iter_mut().collect()`) for `queue_childs`, and nothing pushes
+/// to or otherwise grows/shrinks that same `Vec` afterwards — doing so could reallocate
+/// its backing storage and dangle every pointer already handed out from
+/// it. A `VTag`/`VList`'s `childs` is only ever mutated by the `Patcher`
+/// instruction that owns it (`create`/`diff`), and that instruction runs
+/// to completion (including collecting pointers to the now-final `childs`)
+/// before any pointer derived from it is dereferenced, since the
+/// dereferencing instructions are queued, not run, during that call. The
+/// caller's half of the contract is narrower and just as firm: keep the
+/// root node alive, and don't mutate it (directly, or through another
+/// `&mut` to any node it contains) until `step` stops returning `true`.
+pub struct Patcher<MSG, B: RenderBackend> {
+    stack: Vec<Instruction<MSG, B>>,
+    messages: Messages<MSG>,
 }
 
+impl<MSG, B: RenderBackend> Patcher<MSG, B> {
+    fn new(messages: Messages<MSG>) -> Self {
+        Patcher { stack: Vec::new(), messages }
+    }
+
+    /// Process at most `budget` instructions. Returns `true` if work
+    /// remains and `step` should be called again.
+    pub fn step(&mut self, budget: usize) -> bool {
+        for _ in 0..budget {
+            match self.stack.pop() {
+                Some(instruction) => self.process(instruction),
+                None => return false,
+            }
+        }
+        !self.stack.is_empty()
+    }
+
+    fn drain(&mut self) {
+        while self.step(usize::max_value()) {}
+    }
 
-impl<MSG> VNode<MSG> {
-    fn remove<T: INode>(self, parent: &T) {
-        let opt_ref: Option<Node> = {
-            match self {
-                VNode::VTag { reference, .. } => reference.map(Node::from),
-                VNode::VText { reference, .. } => reference.map(Node::from),
+    fn process(&mut self, instruction: Instruction<MSG, B>) {
+        match instruction {
+            Instruction::Remove { node, parent } => self.remove(node, &parent),
+            // Safe because the caller of `step` promises the tree behind
+            // `new` outlives the `Patcher` and isn't touched elsewhere
+            // while instructions referencing it are still queued.
+            Instruction::Create { new, parent } => unsafe { self.create(&mut *new, &parent) },
+            Instruction::Diff { new, old, parent } => unsafe { self.diff(&mut *new, old, &parent) },
+            // Safe for the same reason as above: by the time a `Reorder`
+            // is popped, every `Diff`/`Create` queued for these same
+            // `childs` (pushed after it, so above it in the stack and
+            // popped first) has already run.
+            Instruction::Reorder { childs, parent } => self.reorder(&childs, &parent),
+        }
+    }
+
+    /// Detach `node`. A `VList` expands into one `Remove` per child
+    /// instead of recursing, like every other instruction here.
+    fn remove(&mut self, node: VNode<MSG, B>, parent: &B::Element) {
+        match node {
+            VNode::VTag { reference, .. } => {
+                if let Some(reference) = reference {
+                    B::remove_child(parent, &B::Node::from(reference));
+                }
+            }
+            VNode::VText { reference, .. } => {
+                if let Some(reference) = reference {
+                    B::remove_child(parent, &B::Node::from(reference));
+                }
+            }
+            VNode::VList { childs } => {
+                for child in childs {
+                    self.stack.push(Instruction::Remove { node: child, parent: parent.clone() });
+                }
             }
-        };
-        if let Some(node) = opt_ref {
-            if let Err(_) = parent.remove_child(&node) {
-                warn!("Node not found to remove: {:?}", node);
+            VNode::Suspended { reference, .. } => {
+                if let Some(reference) = reference {
+                    B::remove_child(parent, &B::Node::from(reference));
+                }
             }
         }
     }
 
-    /// Virtual rendering for the node. It uses parent node and existend children (virtual and DOM)
-    /// to check the difference and apply patches to the actual DOM represenatation.
-    pub fn apply<T: INode>(&mut self, parent: &T, last: Option<VNode<MSG>>, messages: Messages<MSG>) {
-        match *self {
-            VNode::VTag {
-                ref mut vtag,
-                ref mut reference,
-            } => {
+    /// Walk `childs` back-to-front, threading each already-mounted node in
+    /// as the `insert_before` anchor for the one before it. Going in
+    /// reverse means the anchor for a given child is always something
+    /// already placed in its final slot (or `None`, meaning "goes last"),
+    /// so a single backward pass is enough to reach the right order
+    /// regardless of which children were freshly created vs. reused.
+    fn reorder(&mut self, childs: &[*mut VNode<MSG, B>], parent: &B::Element) {
+        let mut anchor: Option<B::Node> = None;
+        for &ptr in childs.iter().rev() {
+            let node = unsafe { &*ptr };
+            if let Some(node_ref) = node.node_ref() {
+                B::insert_before(parent, &node_ref, anchor.as_ref());
+                anchor = Some(node_ref);
+            }
+        }
+    }
+
+    fn create(&mut self, new: &mut VNode<MSG, B>, parent: &B::Element) {
+        match *new {
+            VNode::VTag { ref mut vtag, ref mut reference } => {
+                let element = B::create_element(vtag.tag());
+                B::append_child(parent, &B::Node::from(element.clone()));
+                *reference = Some(element);
+                let element_mut = reference.as_mut().expect("vtag must be here");
+                vtag.render(element_mut, None, self.messages.clone());
+                let lefts = vtag.childs.iter_mut().collect::<Vec<_>>();
+                self.queue_childs(lefts, Vec::new(), element_mut);
+            }
+            VNode::VText { ref mut vtext, ref mut reference } => {
+                let element = B::create_text_node(&vtext.text);
+                B::append_child(parent, &B::Node::from(element.clone()));
+                *reference = Some(element);
+                let element_mut = reference.as_mut().expect("vtext must be here");
+                vtext.render::<B>(element_mut, None);
+            }
+            VNode::VList { ref mut childs } => {
+                let lefts = childs.iter_mut().collect::<Vec<_>>();
+                self.queue_childs(lefts, Vec::new(), parent);
+            }
+            VNode::Suspended { ref mut reference, ref id } => {
+                let element = B::create_text_node("");
+                B::append_child(parent, &B::Node::from(element.clone()));
+                *reference = Some(element);
+                id.set(Some(NodeId::next()));
+            }
+        }
+    }
+
+    fn diff(&mut self, new: &mut VNode<MSG, B>, old: VNode<MSG, B>, parent: &B::Element) {
+        match *new {
+            VNode::VTag { ref mut vtag, ref mut reference } => {
                 let left = vtag;
                 let mut right = None;
-                match last {
-                    Some(VNode::VTag {
-                             vtag,
-                             reference: Some(element),
-                         }) => {
+                match old {
+                    VNode::VTag { vtag, reference: Some(element) } => {
                         // Copy reference from right to left (as is)
                         if left.tag() == vtag.tag() {
                             right = Some(vtag);
                             *reference = Some(element);
                         } else {
                             let wrong = element;
-                            let element = document().create_element(left.tag());
-                            parent.replace_child(&element, &wrong);
+                            let element = B::create_element(left.tag());
+                            B::replace_child(parent, &B::Node::from(element.clone()), &B::Node::from(wrong));
                             *reference = Some(element);
                         }
                     }
-                    Some(VNode::VText { reference: Some(wrong), .. }) => {
-                        let element = document().create_element(left.tag());
-                        parent.replace_child(&element, &wrong);
+                    VNode::VText { reference: Some(wrong), .. } => {
+                        let element = B::create_element(left.tag());
+                        B::replace_child(parent, &B::Node::from(element.clone()), &B::Node::from(wrong));
                         *reference = Some(element);
                     }
-                    Some(VNode::VTag { reference: None, .. }) |
-                    Some(VNode::VText { reference: None, .. }) |
-                    None => {
-                        let element = document().create_element(left.tag());
-                        parent.append_child(&element);
+                    VNode::Suspended { reference: Some(wrong), .. } => {
+                        let element = B::create_element(left.tag());
+                        B::replace_child(parent, &B::Node::from(element.clone()), &B::Node::from(wrong));
+                        *reference = Some(element);
+                    }
+                    list @ VNode::VList { .. } => {
+                        self.stack.push(Instruction::Remove { node: list, parent: parent.clone() });
+                        let element = B::create_element(left.tag());
+                        B::append_child(parent, &B::Node::from(element.clone()));
+                        *reference = Some(element);
+                    }
+                    VNode::VTag { reference: None, .. } |
+                    VNode::VText { reference: None, .. } |
+                    VNode::Suspended { reference: None, .. } => {
+                        let element = B::create_element(left.tag());
+                        B::append_child(parent, &B::Node::from(element.clone()));
                         *reference = Some(element);
                     }
                 }
                 let element_mut = reference.as_mut().expect("vtag must be here");
-                // Update parameters
-                let mut rights = {
+                let rights = {
                     if let Some(ref mut right) = right {
-                        right.childs.drain(..).map(Some).collect::<Vec<_>>()
+                        right.childs.drain(..).collect::<Vec<_>>()
                     } else {
                         Vec::new()
                     }
                 };
-                // TODO Consider to use: &mut Messages here;
-                left.render(element_mut, right, messages.clone());
-                let mut lefts = left.childs.iter_mut().map(Some).collect::<Vec<_>>();
-                // Process children
-                let diff = lefts.len() as i32 - rights.len() as i32;
-                if diff > 0 {
-                    for _ in 0..diff {
-                        rights.push(None);
-                    }
-                } else if diff < 0 {
-                    for _ in 0..-diff {
-                        lefts.push(None);
-                    }
-                }
-                for pair in lefts.into_iter().zip(rights) {
-                    match pair {
-                        (Some(left), right) => {
-                            left.apply(element_mut, right, messages.clone());
-                        }
-                        (None, Some(right)) => {
-                            right.remove(element_mut);
-                        }
-                        (None, None) => {
-                            panic!("redundant iterations during diff");
-                        }
+                left.render(element_mut, right, self.messages.clone());
+                let lefts = left.childs.iter_mut().collect::<Vec<_>>();
+                self.queue_childs(lefts, rights, element_mut);
+            }
+            VNode::VList { ref mut childs } => {
+                let rights = match old {
+                    VNode::VList { childs } => childs,
+                    other => {
+                        self.stack.push(Instruction::Remove { node: other, parent: parent.clone() });
+                        Vec::new()
                     }
-                }
-                //vtag.apply(parent, reference, last, messages);
+                };
+                let lefts = childs.iter_mut().collect::<Vec<_>>();
+                self.queue_childs(lefts, rights, parent);
             }
-            VNode::VText {
-                ref mut vtext,
-                ref mut reference,
-            } => {
+            VNode::VText { ref mut vtext, ref mut reference } => {
                 let left = vtext;
                 let mut right = None;
-                match last {
-                    Some(VNode::VText {
-                             vtext,
-                             reference: Some(element),
-                         }) => {
+                match old {
+                    VNode::VText { vtext, reference: Some(element) } => {
                         right = Some(vtext);
                         *reference = Some(element);
                     }
-                    Some(VNode::VTag { reference: Some(wrong), .. }) => {
-                        let element = document().create_text_node(&left.text);
-                        parent.replace_child(&element, &wrong);
+                    VNode::VTag { reference: Some(wrong), .. } => {
+                        let element = B::create_text_node(&left.text);
+                        B::replace_child(parent, &B::Node::from(element.clone()), &B::Node::from(wrong));
                         *reference = Some(element);
                     }
-                    Some(VNode::VTag { reference: None, .. }) |
-                    Some(VNode::VText { reference: None, .. }) |
-                    None => {
-                        let element = document().create_text_node(&left.text);
-                        parent.append_child(&element);
+                    VNode::Suspended { reference: Some(wrong), .. } => {
+                        let element = B::create_text_node(&left.text);
+                        B::replace_child(parent, &B::Node::from(element.clone()), &B::Node::from(wrong));
+                        *reference = Some(element);
+                    }
+                    list @ VNode::VList { .. } => {
+                        self.stack.push(Instruction::Remove { node: list, parent: parent.clone() });
+                        let element = B::create_text_node(&left.text);
+                        B::append_child(parent, &B::Node::from(element.clone()));
+                        *reference = Some(element);
+                    }
+                    VNode::VTag { reference: None, .. } |
+                    VNode::VText { reference: None, .. } |
+                    VNode::Suspended { reference: None, .. } => {
+                        let element = B::create_text_node(&left.text);
+                        B::append_child(parent, &B::Node::from(element.clone()));
                         *reference = Some(element);
                     }
                 }
                 let element_mut = reference.as_mut().expect("vtext must be here");
-                left.render(element_mut, right);
+                left.render::<B>(element_mut, right);
+            }
+            VNode::Suspended { ref mut reference, ref id } => {
+                match old {
+                    VNode::Suspended { reference: Some(element), id: old_id } => {
+                        *reference = Some(element);
+                        id.set(old_id.get());
+                    }
+                    VNode::VTag { reference: Some(wrong), .. } => {
+                        let element = B::create_text_node("");
+                        B::replace_child(parent, &B::Node::from(element.clone()), &B::Node::from(wrong));
+                        *reference = Some(element);
+                        id.set(Some(NodeId::next()));
+                    }
+                    VNode::VText { reference: Some(wrong), .. } => {
+                        let element = B::create_text_node("");
+                        B::replace_child(parent, &B::Node::from(element.clone()), &B::Node::from(wrong));
+                        *reference = Some(element);
+                        id.set(Some(NodeId::next()));
+                    }
+                    list @ VNode::VList { .. } => {
+                        self.stack.push(Instruction::Remove { node: list, parent: parent.clone() });
+                        let element = B::create_text_node("");
+                        B::append_child(parent, &B::Node::from(element.clone()));
+                        *reference = Some(element);
+                        id.set(Some(NodeId::next()));
+                    }
+                    VNode::VTag { reference: None, .. } |
+                    VNode::VText { reference: None, .. } |
+                    VNode::Suspended { reference: None, .. } => {
+                        let element = B::create_text_node("");
+                        B::append_child(parent, &B::Node::from(element.clone()));
+                        *reference = Some(element);
+                        id.set(Some(NodeId::next()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queue a diff of a list of new children against a list of old
+    /// children. Shared by the `VTag` and `VList` cases of `diff`/`create`
+    /// so both get keyed reconciliation and removal for free.
+    fn queue_childs(
+        &mut self,
+        lefts: Vec<&mut VNode<MSG, B>>,
+        rights: Vec<VNode<MSG, B>>,
+        parent: &B::Element,
+    ) {
+        if rights.iter().any(|right| right.key().is_some()) {
+            // Keyed reconciliation: pair new children up with the old
+            // node that carries the same key (if any), so reordering
+            // or inserting a single item doesn't disturb the rest.
+            let mut keyed: HashMap<String, VNode<MSG, B>> = HashMap::new();
+            let mut unkeyed: VecDeque<VNode<MSG, B>> = VecDeque::new();
+            for right in rights {
+                match right.key().map(|key| key.to_string()) {
+                    Some(key) => {
+                        keyed.insert(key, right);
+                    }
+                    None => {
+                        unkeyed.push_back(right);
+                    }
+                }
+            }
+            // Pairing must walk `lefts` in source order, since unkeyed
+            // children are matched positionally against `unkeyed` via
+            // `pop_front`. The instructions are then pushed in reverse so
+            // the LIFO `stack.pop()` in `step` still processes (and so
+            // mounts/appends) children in source order.
+            let mut pairs = Vec::with_capacity(lefts.len());
+            for left in lefts {
+                // A keyed child only ever pairs with the old node that
+                // carries the same key: falling back to an unrelated
+                // positional node would hand it a stale `reference` that
+                // isn't actually the element this key used to own. A
+                // keyed child with no match is a genuinely new item.
+                let right = match left.key() {
+                    Some(key) => keyed.remove(key),
+                    None => unkeyed.pop_front(),
+                };
+                pairs.push((left as *mut VNode<MSG, B>, right));
+            }
+            // Reusing a matched old reference doesn't move it, and a
+            // freshly `Create`d one only ever lands at the end of
+            // `parent` — neither puts a child in its final sibling slot
+            // on its own. Queue a `Reorder` over all of them, deeper in
+            // the stack than their own `Diff`/`Create` so it only runs
+            // once every child here is mounted somewhere under `parent`.
+            let order = pairs.iter().map(|&(new, _)| new).collect();
+            self.stack.push(Instruction::Reorder { childs: order, parent: parent.clone() });
+            for (new, right) in pairs.into_iter().rev() {
+                match right {
+                    Some(old) => self.stack.push(Instruction::Diff { new, old, parent: parent.clone() }),
+                    None => self.stack.push(Instruction::Create { new, parent: parent.clone() }),
+                }
+            }
+            for (_, right) in keyed {
+                self.stack.push(Instruction::Remove { node: right, parent: parent.clone() });
+            }
+            for right in unkeyed {
+                self.stack.push(Instruction::Remove { node: right, parent: parent.clone() });
             }
+        } else {
+            let mut lefts = lefts.into_iter().map(Some).collect::<Vec<_>>();
+            let mut rights = rights.into_iter().map(Some).collect::<Vec<_>>();
+            let diff = lefts.len() as i32 - rights.len() as i32;
+            if diff > 0 {
+                for _ in 0..diff {
+                    rights.push(None);
+                }
+            } else if diff < 0 {
+                for _ in 0..-diff {
+                    lefts.push(None);
+                }
+            }
+            // Pushed in reverse so the LIFO `stack.pop()` in `step` still
+            // processes (and so mounts/appends) children in source order.
+            for pair in lefts.into_iter().zip(rights).rev() {
+                match pair {
+                    (Some(left), Some(old)) => {
+                        let new = left as *mut VNode<MSG, B>;
+                        self.stack.push(Instruction::Diff { new, old, parent: parent.clone() });
+                    }
+                    (Some(left), None) => {
+                        let new = left as *mut VNode<MSG, B>;
+                        self.stack.push(Instruction::Create { new, parent: parent.clone() });
+                    }
+                    (None, Some(right)) => {
+                        self.stack.push(Instruction::Remove { node: right, parent: parent.clone() });
+                    }
+                    (None, None) => {
+                        panic!("redundant iterations during diff");
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<MSG, B: RenderBackend> VNode<MSG, B> {
+    /// The key of this node, if it was given one. Used to pair up nodes
+    /// across renders instead of relying on their position in the list.
+    fn key(&self) -> Option<&str> {
+        match *self {
+            VNode::VTag { ref vtag, .. } => vtag.key(),
+            VNode::VText { ref vtext, .. } => vtext.key(),
+            VNode::VList { .. } => None,
+            VNode::Suspended { .. } => None,
+        }
+    }
+
+    /// The mounted node this vtree is currently occupying, if any. A
+    /// `VList` has no element of its own, so it defers to its first child
+    /// that has one. Used to find an `insert_before` anchor when
+    /// repositioning keyed children.
+    fn node_ref(&self) -> Option<B::Node> {
+        match *self {
+            VNode::VTag { ref reference, .. } => reference.clone().map(B::Node::from),
+            VNode::VText { ref reference, .. } => reference.clone().map(B::Node::from),
+            VNode::Suspended { ref reference, .. } => reference.clone().map(B::Node::from),
+            VNode::VList { ref childs } => childs.iter().filter_map(VNode::node_ref).next(),
+        }
+    }
+
+    /// A not-yet-rendered placeholder slot. `apply` mounts it as an empty
+    /// node and reserves a stable id immediately, so a later `apply` can
+    /// replace it in place once the real content resolves.
+    pub fn suspended() -> Self {
+        VNode::Suspended {
+            reference: None,
+            id: Cell::new(None),
         }
     }
+
+    /// The id reserved for this slot by `apply`, if it has run at least
+    /// once. `None` before the first `apply`, or for any other variant.
+    pub fn suspended_id(&self) -> Option<NodeId> {
+        match *self {
+            VNode::Suspended { ref id, .. } => id.get(),
+            _ => None,
+        }
+    }
+
+    /// Start a diff/apply pass without running it. The returned `Patcher`
+    /// can be driven a few instructions at a time via `Patcher::step`
+    /// instead of blocking the caller until the whole tree is walked.
+    pub fn begin_apply(
+        &mut self,
+        parent: &B::Element,
+        last: Option<VNode<MSG, B>>,
+        messages: Messages<MSG>,
+    ) -> Patcher<MSG, B> {
+        let mut patcher = Patcher::new(messages);
+        let new = self as *mut VNode<MSG, B>;
+        match last {
+            Some(old) => patcher.stack.push(Instruction::Diff { new, old, parent: parent.clone() }),
+            None => patcher.stack.push(Instruction::Create { new, parent: parent.clone() }),
+        }
+        patcher
+    }
+
+    /// Virtual rendering for the node. It uses parent node and existend children (virtual and DOM)
+    /// to check the difference and apply patches to the actual DOM represenatation.
+    ///
+    /// Runs a `Patcher` to completion in one synchronous burst, so its
+    /// observable result is exactly what the old recursive `apply` produced.
+    pub fn apply(&mut self, parent: &B::Element, last: Option<VNode<MSG, B>>, messages: Messages<MSG>) {
+        self.begin_apply(parent, last, messages).drain();
+    }
 }
 
-impl<MSG> From<VText> for VNode<MSG> {
+impl<MSG, B: RenderBackend> From<VText> for VNode<MSG, B> {
     fn from(vtext: VText) -> Self {
         VNode::VText {
             reference: None,
@@ -159,8 +778,8 @@ impl<MSG> From<VText> for VNode<MSG> {
     }
 }
 
-impl<MSG> From<VTag<MSG>> for VNode<MSG> {
-    fn from(vtag: VTag<MSG>) -> Self {
+impl<MSG, B: RenderBackend> From<VTag<MSG, B>> for VNode<MSG, B> {
+    fn from(vtag: VTag<MSG, B>) -> Self {
         VNode::VTag {
             reference: None,
             vtag,
@@ -168,26 +787,54 @@ impl<MSG> From<VTag<MSG>> for VNode<MSG> {
     }
 }
 
-impl<MSG, T: ToString> From<T> for VNode<MSG> {
-    fn from(value: T) -> Self {
-        VNode::VText {
-            reference: None,
-            vtext: VText::new(value),
-        }
+macro_rules! impl_from_text {
+    ($($type:ty),+ $(,)*) => {
+        $(
+            impl<MSG, B: RenderBackend> From<$type> for VNode<MSG, B> {
+                fn from(value: $type) -> Self {
+                    VNode::VText {
+                        reference: None,
+                        vtext: VText::new(value),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+impl_from_text! {
+    String, &'static str,
+    bool, char,
+    u8, u16, u32, u64, usize,
+    i8, i16, i32, i64, isize,
+    f32, f64,
+}
+
+impl<MSG, B: RenderBackend> From<Vec<VNode<MSG, B>>> for VNode<MSG, B> {
+    fn from(childs: Vec<VNode<MSG, B>>) -> Self {
+        VNode::VList { childs }
+    }
+}
+
+impl<MSG, B: RenderBackend> FromIterator<VNode<MSG, B>> for VNode<MSG, B> {
+    fn from_iter<IT: IntoIterator<Item = VNode<MSG, B>>>(iter: IT) -> Self {
+        VNode::VList { childs: iter.into_iter().collect() }
     }
 }
 
-impl<MSG> fmt::Debug for VNode<MSG> {
+impl<MSG, B: RenderBackend> fmt::Debug for VNode<MSG, B> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             &VNode::VTag { ref vtag, .. } => vtag.fmt(f),
             &VNode::VText { ref vtext, .. } => vtext.fmt(f),
+            &VNode::VList { ref childs } => childs.fmt(f),
+            &VNode::Suspended { ref id, .. } => write!(f, "Suspended {{ id: {:?} }}", id.get()),
         }
     }
 }
 
-impl<MSG> PartialEq for VNode<MSG> {
-    fn eq(&self, other: &VNode<MSG>) -> bool {
+impl<MSG, B: RenderBackend> PartialEq for VNode<MSG, B> {
+    fn eq(&self, other: &VNode<MSG, B>) -> bool {
         match *self {
             VNode::VTag { vtag: ref vtag_a, .. } => {
                 match *other {
@@ -204,7 +851,99 @@ impl<MSG> PartialEq for VNode<MSG> {
                     },
                     _ => false
                 }
+            },
+            VNode::VList { childs: ref childs_a } => {
+                match *other {
+                    VNode::VList { childs: ref childs_b } => {
+                        childs_a == childs_b
+                    },
+                    _ => false
+                }
             }
+            VNode::Suspended { .. } => {
+                match *other {
+                    VNode::Suspended { .. } => true,
+                    _ => false
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `Messages<MSG>` has no public constructor in this tree; every
+    // concrete `Messages` is expected to implement `Default` as an empty
+    // outgoing-message queue.
+    #[test]
+    fn mounts_children_in_source_order() {
+        let mut root: VNode<(), MemoryBackend> = vec![
+            VNode::from("a"),
+            VNode::from("b"),
+            VNode::from("c"),
+        ].into_iter().collect();
+        let parent = MemoryBackend::create_element("div");
+        root.apply(&parent, None, Messages::default());
+        assert_eq!(parent.to_string(), "<div>abc</div>");
+    }
+
+    #[test]
+    fn serializes_attributes_and_children() {
+        let div = MemoryBackend::create_element("div");
+        MemoryBackend::set_attribute(&div, "class", "card");
+        let text = MemoryBackend::create_text_node("hi");
+        MemoryBackend::append_child(&div, &MemoryNode::from(text));
+        assert_eq!(div.to_string(), "<div class=\"card\">hi</div>");
+    }
+
+    /// A keyed `VText`, for exercising the keyed branch of `queue_childs`
+    /// without depending on `VTag`'s own (non-text) children.
+    fn keyed(key: &'static str, text: &'static str) -> VNode<(), MemoryBackend> {
+        VNode::VText {
+            reference: None,
+            vtext: VText::new(text).with_key(key),
         }
     }
+
+    #[test]
+    fn repositions_keyed_children_on_reorder() {
+        let mut initial: VNode<(), MemoryBackend> =
+            vec![keyed("a", "A"), keyed("b", "B")].into_iter().collect();
+        let parent = MemoryBackend::create_element("div");
+        initial.apply(&parent, None, Messages::default());
+        assert_eq!(parent.to_string(), "<div>AB</div>");
+
+        let mut reordered: VNode<(), MemoryBackend> =
+            vec![keyed("b", "B"), keyed("a", "A")].into_iter().collect();
+        reordered.apply(&parent, Some(initial), Messages::default());
+        assert_eq!(parent.to_string(), "<div>BA</div>");
+    }
+
+    #[test]
+    fn repositions_keyed_children_on_insert() {
+        let mut initial: VNode<(), MemoryBackend> =
+            vec![keyed("a", "A"), keyed("b", "B")].into_iter().collect();
+        let parent = MemoryBackend::create_element("div");
+        initial.apply(&parent, None, Messages::default());
+
+        let mut inserted: VNode<(), MemoryBackend> =
+            vec![keyed("a", "A"), keyed("x", "X"), keyed("b", "B")].into_iter().collect();
+        inserted.apply(&parent, Some(initial), Messages::default());
+        assert_eq!(parent.to_string(), "<div>AXB</div>");
+    }
+
+    #[test]
+    fn removes_keyed_children() {
+        let mut initial: VNode<(), MemoryBackend> =
+            vec![keyed("a", "A"), keyed("b", "B"), keyed("c", "C")].into_iter().collect();
+        let parent = MemoryBackend::create_element("div");
+        initial.apply(&parent, None, Messages::default());
+
+        let mut removed: VNode<(), MemoryBackend> =
+            vec![keyed("a", "A"), keyed("c", "C")].into_iter().collect();
+        removed.apply(&parent, Some(initial), Messages::default());
+        assert_eq!(parent.to_string(), "<div>AC</div>");
+    }
 }